@@ -0,0 +1,84 @@
+use super::vga_buffer::{self, ACTUAL_BUFFER_WIDTH};
+
+// Fixed-capacity, stack-only stand-in for a heap `String` (ArrayString-style)
+// so a committed line can be handed to a command dispatcher without `alloc`.
+pub struct LineBuffer {
+    bytes: [u8; ACTUAL_BUFFER_WIDTH],
+    len: usize,
+}
+
+impl LineBuffer {
+    const fn new() -> Self {
+        LineBuffer {
+            bytes: [0; ACTUAL_BUFFER_WIDTH],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < self.bytes.len() {
+            self.bytes[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+// Maintains an editable input line on the bottom row, reading the VGA
+// buffer back to reconstruct it rather than shadowing it in RAM. The
+// `prompt_column` marks the end of the prompt the line starts after; the
+// cursor is never allowed left of it.
+pub struct LineEditor {
+    prompt_column: usize,
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        LineEditor { prompt_column: 0 }
+    }
+
+    // Call once the prompt itself has been printed. The writer leaves the
+    // cursor on the prompt's last character, so it is stepped one column
+    // right and that column becomes the protected boundary for this line.
+    pub fn begin_line(&mut self) {
+        vga_buffer::cursor_front();
+        self.prompt_column = vga_buffer::column_position();
+    }
+
+    pub fn insert(&mut self, byte: u8) {
+        vga_buffer::insert_at_cursor(byte);
+    }
+
+    pub fn delete_back(&mut self) {
+        if vga_buffer::column_position() > self.prompt_column {
+            vga_buffer::cursor_back();
+            vga_buffer::delete_at_cursor();
+        }
+    }
+
+    pub fn move_left(&mut self) {
+        if vga_buffer::column_position() > self.prompt_column {
+            vga_buffer::cursor_back();
+        }
+    }
+
+    pub fn move_right(&mut self) {
+        vga_buffer::cursor_front();
+    }
+
+    // Extracts everything typed after the prompt as the committed line,
+    // for the caller to hand off to a command dispatcher on Enter.
+    pub fn commit(&mut self) -> LineBuffer {
+        let (bytes, len) = vga_buffer::read_row();
+        let start = self.prompt_column.min(len);
+        let mut line = LineBuffer::new();
+        for &byte in &bytes[start..len] {
+            line.push(byte);
+        }
+        self.prompt_column = 0;
+        line
+    }
+}
@@ -0,0 +1,27 @@
+pub mod line_editor;
+pub mod serial;
+pub mod vga_buffer;
+
+use core::fmt::Write;
+use x86_64::instructions::interrupts;
+
+// Fans a single call out to both the VGA buffer and the serial port, so
+// output (and panics) survive a corrupted or headless screen. Guarded by
+// `without_interrupts` the same way `vga_buffer::print`/`serial::print` are.
+#[doc(hidden)]
+pub fn log(args: core::fmt::Arguments) {
+    interrupts::without_interrupts(|| {
+        vga_buffer::WRITER.lock().write_fmt(args).unwrap();
+        serial::SERIAL1
+            .lock()
+            .write_fmt(args)
+            .expect("Printing to serial failed");
+    });
+}
+
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::low_level::log(format_args!($($arg)*));
+    };
+}
@@ -2,7 +2,9 @@ use crate::print;
 use core::fmt::{self, Write};
 use lazy_static::lazy_static;
 use spin::Mutex;
+use volatile::Volatile;
 use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -38,10 +40,6 @@ impl ColorCode {
     fn get_colors(&self) -> (u8, u8) {
         (self.0 % 16u8, self.0 >> 4u8)
     }
-    fn invert(&mut self) {
-        let colors = self.get_colors();
-        *self = Self::generate(colors.1, colors.0)
-    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -50,39 +48,188 @@ struct Char {
     ascii_character: u8,
     color_code: ColorCode,
 }
-impl Char {
-    fn invert_colors(&mut self) {
-        self.color_code.invert();
-    }
-}
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
-const ACTUAL_BUFFER_WIDTH: usize = 50;
+pub(crate) const BUFFER_HEIGHT: usize = 25;
+pub(crate) const BUFFER_WIDTH: usize = 80;
+pub(crate) const ACTUAL_BUFFER_WIDTH: usize = 50;
 //Added because input stopped working after user tried to enter the 51 character.
 //Probably qemu issue, maybe there is a way, but this is the temporary fix
 #[repr(transparent)]
 struct Buffer {
-    chars: [[Char; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    chars: [[Volatile<Char>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+}
+
+// Minimal vte-style state machine for ANSI/VT100 escape sequences so callers
+// can embed `\x1b[...m` etc. in the byte stream instead of calling
+// `set_color` out of band.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnsiState {
+    Ground,
+    Escape,
+    CsiParam,
+}
+
+// Fixed upper bound on `;`-separated CSI parameters, kept small since real
+// sequences (SGR, CUP, ED, EL) never need more than a couple.
+const MAX_CSI_PARAMS: usize = 8;
+
+// Upper bound on the raw bytes of one escape sequence (ESC, `[`,
+// digits/`;`, final byte) we keep around so an unrecognized sequence can be
+// echoed verbatim instead of silently eaten. Bytes past this are dropped
+// from the echo, not from the input stream.
+const MAX_CSI_RAW: usize = 16;
+
+const BLANK_CHAR: Char = Char {
+    ascii_character: b' ',
+    color_code: ColorCode(0),
+};
+
+// Number of rows of history retained once they scroll off the top of the
+// screen. Statically sized so the whole thing lives in a `static`
+// (`SCROLLBACK_STORAGE` below), no heap involved.
+const SCROLLBACK_ROWS: usize = 1000;
+
+// Fixed-capacity ring buffer of evicted rows, oldest overwritten first once
+// full.
+struct Scrollback {
+    rows: [[Char; BUFFER_WIDTH]; SCROLLBACK_ROWS],
+    start: usize,
+    len: usize,
+}
+
+impl Scrollback {
+    const fn new() -> Self {
+        Scrollback {
+            rows: [[BLANK_CHAR; BUFFER_WIDTH]; SCROLLBACK_ROWS],
+            start: 0,
+            len: 0,
+        }
+    }
+    fn push(&mut self, row: [Char; BUFFER_WIDTH]) {
+        let index = (self.start + self.len) % SCROLLBACK_ROWS;
+        self.rows[index] = row;
+        if self.len < SCROLLBACK_ROWS {
+            self.len += 1;
+        } else {
+            self.start = (self.start + 1) % SCROLLBACK_ROWS;
+        }
+    }
+    // Row `distance` positions back from the most recently evicted one (0 =
+    // newest). Returns a blank row once `distance` runs past retained history.
+    fn row_from_newest(&self, distance: usize) -> [Char; BUFFER_WIDTH] {
+        if distance >= self.len {
+            return [BLANK_CHAR; BUFFER_WIDTH];
+        }
+        let index = (self.start + self.len - 1 - distance) % SCROLLBACK_ROWS;
+        self.rows[index]
+    }
 }
 
+// `Scrollback` and the live-view snapshot are ~160 KB and ~4 KB
+// respectively. They live here as plain `static mut`s - whose initializers
+// are evaluated by the compiler and placed directly in the binary's data
+// section - rather than as `Writer` fields, so that building `WRITER`
+// never has to construct (and temporarily hold on the stack) a value that
+// size; `Writer` only ever holds `'static` references to them, the same
+// way it already holds a reference to the raw VGA `Buffer`.
+static mut SCROLLBACK_STORAGE: Scrollback = Scrollback::new();
+static mut LIVE_SNAPSHOT_STORAGE: [[Char; BUFFER_WIDTH]; BUFFER_HEIGHT] =
+    [[BLANK_CHAR; BUFFER_WIDTH]; BUFFER_HEIGHT];
+
 pub struct Writer {
     column_position: usize,
+    row_position: usize,
     color_code: ColorCode,
     buffer: &'static mut Buffer,
+    ansi_state: AnsiState,
+    csi_params: [u16; MAX_CSI_PARAMS],
+    csi_param_count: usize,
+    // Raw bytes of the escape sequence in progress, replayed verbatim if it
+    // turns out to be malformed.
+    csi_raw: [u8; MAX_CSI_RAW],
+    csi_raw_len: usize,
+    scrollback: &'static mut Scrollback,
+    // Rows of the live screen as they were just before scrolling away from
+    // it, used to repaint the bottom of the viewport and to restore the
+    // real content when scrolling back down to it.
+    live_snapshot: &'static mut [[Char; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    // 0 while viewing the live screen; otherwise how many rows above it
+    // the viewport has been scrolled.
+    view_offset: usize,
 }
 
 impl Writer {
     pub fn move_cursor(&mut self, column_position: usize) {
-        self.buffer.chars[BUFFER_HEIGHT - 1][self.column_position + 1].invert_colors();
         if column_position == 0 {
             self.next_line();
         } else {
             self.column_position = column_position;
         }
-        self.buffer.chars[BUFFER_HEIGHT - 1][self.column_position + 1].invert_colors();
+        self.update_hardware_cursor();
+    }
+    // Programs the VGA CRTC text-mode cursor to the current row/column via
+    // index port 0x3D4 and data port 0x3D5, replacing the old inverted-cell
+    // fake cursor.
+    fn update_hardware_cursor(&self) {
+        let position = self.row_position * BUFFER_WIDTH + self.column_position;
+        let mut index_port: Port<u8> = Port::new(0x3D4);
+        let mut data_port: Port<u8> = Port::new(0x3D5);
+        unsafe {
+            index_port.write(0x0F);
+            data_port.write((position & 0xFF) as u8);
+            index_port.write(0x0E);
+            data_port.write(((position >> 8) & 0xFF) as u8);
+        }
+    }
+    pub fn enable_cursor(&self) {
+        let mut index_port: Port<u8> = Port::new(0x3D4);
+        let mut data_port: Port<u8> = Port::new(0x3D5);
+        unsafe {
+            index_port.write(0x0A);
+            let start = data_port.read() & 0xC0;
+            data_port.write(start);
+            index_port.write(0x0B);
+            let end = (data_port.read() & 0xE0) | 0x0F;
+            data_port.write(end);
+        }
+    }
+    pub fn disable_cursor(&self) {
+        let mut index_port: Port<u8> = Port::new(0x3D4);
+        let mut data_port: Port<u8> = Port::new(0x3D5);
+        unsafe {
+            index_port.write(0x0A);
+            data_port.write(0x20);
+        }
     }
     pub fn write_byte(&mut self, byte: u8) {
+        self.snap_to_bottom();
+        match self.ansi_state {
+            AnsiState::Ground => {
+                if byte == 0x1b {
+                    self.csi_raw_len = 0;
+                    self.push_csi_raw(0x1b);
+                    self.ansi_state = AnsiState::Escape;
+                    return;
+                }
+                self.write_byte_raw(byte);
+            }
+            AnsiState::Escape => {
+                if byte == b'[' {
+                    self.csi_params = [0; MAX_CSI_PARAMS];
+                    self.csi_param_count = 0;
+                    self.push_csi_raw(b'[');
+                    self.ansi_state = AnsiState::CsiParam;
+                } else {
+                    // Malformed escape sequence (no CSI): print it verbatim.
+                    self.ansi_state = AnsiState::Ground;
+                    self.write_byte_raw(0x1b);
+                    self.write_byte_raw(byte);
+                }
+            }
+            AnsiState::CsiParam => self.write_csi_param_byte(byte),
+        }
+    }
+    fn write_byte_raw(&mut self, byte: u8) {
         if byte == b'\n' || self.column_position >= ACTUAL_BUFFER_WIDTH {
             self.move_cursor(0);
             return;
@@ -90,20 +237,199 @@ impl Writer {
         self.move_cursor(self.column_position + 1);
         self.set_char(byte);
     }
+    fn push_csi_raw(&mut self, byte: u8) {
+        if let Some(slot) = self.csi_raw.get_mut(self.csi_raw_len) {
+            *slot = byte;
+            self.csi_raw_len += 1;
+        }
+    }
+    fn write_csi_param_byte(&mut self, byte: u8) {
+        self.push_csi_raw(byte);
+        match byte {
+            b'0'..=b'9' => {
+                if self.csi_param_count == 0 {
+                    self.csi_param_count = 1;
+                }
+                if let Some(param) = self.csi_params.get_mut(self.csi_param_count - 1) {
+                    *param = param
+                        .saturating_mul(10)
+                        .saturating_add((byte - b'0') as u16);
+                }
+            }
+            b';' => {
+                if self.csi_param_count < MAX_CSI_PARAMS {
+                    self.csi_param_count += 1;
+                }
+            }
+            b'm' | b'H' | b'f' | b'J' | b'K' => {
+                self.dispatch_csi(byte);
+                self.ansi_state = AnsiState::Ground;
+            }
+            _ => {
+                // Unrecognized final byte: malformed sequence, fall back to
+                // printing the raw bytes seen so far instead of eating them.
+                self.ansi_state = AnsiState::Ground;
+                let raw = self.csi_raw;
+                for &byte in &raw[..self.csi_raw_len] {
+                    self.write_byte_raw(byte);
+                }
+            }
+        }
+    }
+    // Returns an owned copy of the parsed CSI parameters (rather than a
+    // slice borrowing `self`) so callers are free to mutate other fields of
+    // `self`, such as `color_code`, while iterating over them.
+    fn csi_params(&self) -> ([u16; MAX_CSI_PARAMS], usize) {
+        let count = if self.csi_param_count == 0 {
+            1
+        } else {
+            self.csi_param_count
+        };
+        (self.csi_params, count)
+    }
+    fn dispatch_csi(&mut self, final_byte: u8) {
+        match final_byte {
+            b'm' => self.apply_sgr(),
+            b'H' | b'f' => self.apply_cursor_position(),
+            b'J' => self.erase_screen(),
+            b'K' => self.clear_row(self.row_position),
+            _ => {}
+        }
+    }
+    fn apply_sgr(&mut self) {
+        let (params, count) = self.csi_params();
+        for &param in &params[..count] {
+            match param {
+                0 => self.set_color(Color::Yellow, Color::Black),
+                30..=37 => {
+                    self.color_code = ColorCode::generate(
+                        sgr_color_index(param - 30),
+                        self.color_code.get_colors().1,
+                    )
+                }
+                90..=97 => {
+                    self.color_code = ColorCode::generate(
+                        sgr_color_index(8 + (param - 90)),
+                        self.color_code.get_colors().1,
+                    )
+                }
+                40..=47 => {
+                    self.color_code = ColorCode::generate(
+                        self.color_code.get_colors().0,
+                        sgr_color_index(param - 40),
+                    )
+                }
+                100..=107 => {
+                    self.color_code = ColorCode::generate(
+                        self.color_code.get_colors().0,
+                        sgr_color_index(8 + (param - 100)),
+                    )
+                }
+                _ => {}
+            }
+        }
+    }
+    fn apply_cursor_position(&mut self) {
+        let (params, count) = self.csi_params();
+        let params = &params[..count];
+        let row = (*params.first().unwrap_or(&1)).max(1) as usize - 1;
+        let column = (*params.get(1).unwrap_or(&1)).max(1) as usize - 1;
+        self.row_position = row.min(BUFFER_HEIGHT - 1);
+        self.column_position = column.min(ACTUAL_BUFFER_WIDTH - 1);
+        self.update_hardware_cursor();
+    }
+    fn erase_screen(&mut self) {
+        for row in 0..BUFFER_HEIGHT {
+            self.clear_row(row);
+        }
+        self.row_position = BUFFER_HEIGHT - 1;
+        self.column_position = 0;
+        self.update_hardware_cursor();
+    }
     fn set_char(&mut self, byte: u8) {
-        self.buffer.chars[BUFFER_HEIGHT - 1][self.column_position] = Char {
+        self.buffer.chars[self.row_position][self.column_position].write(Char {
             ascii_character: byte,
             color_code: self.color_code,
-        };
+        });
     }
     fn next_line(&mut self) {
+        let mut evicted = [BLANK_CHAR; BUFFER_WIDTH];
+        for col in 0..BUFFER_WIDTH {
+            evicted[col] = self.buffer.chars[0][col].read();
+        }
+        self.scrollback.push(evicted);
         for row in 1..BUFFER_HEIGHT {
-            self.buffer.chars[row - 1] = self.buffer.chars[row]
+            for col in 0..BUFFER_WIDTH {
+                let character = self.buffer.chars[row][col].read();
+                self.buffer.chars[row - 1][col].write(character);
+            }
         }
+        self.row_position = BUFFER_HEIGHT - 1;
         self.clear_row(BUFFER_HEIGHT - 1);
         self.column_position = 0;
     }
 
+    // Scrolls the viewport `lines` rows further into history, repainting
+    // the visible window from the scrollback ring. Clamped to the amount
+    // of history actually retained.
+    pub fn scroll_up(&mut self, lines: usize) {
+        if self.view_offset == 0 {
+            self.capture_live_snapshot();
+            self.disable_cursor();
+        }
+        self.view_offset = (self.view_offset + lines).min(self.scrollback.len);
+        self.repaint_viewport();
+    }
+
+    // Scrolls the viewport back towards the live screen, snapping to it
+    // (and re-enabling the cursor) once `lines` would go past it.
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.view_offset = self.view_offset.saturating_sub(lines);
+        self.repaint_viewport();
+        if self.view_offset == 0 {
+            self.enable_cursor();
+            self.update_hardware_cursor();
+        }
+    }
+
+    fn snap_to_bottom(&mut self) {
+        if self.view_offset != 0 {
+            self.view_offset = 0;
+            self.repaint_viewport();
+            self.enable_cursor();
+            self.update_hardware_cursor();
+        }
+    }
+
+    fn capture_live_snapshot(&mut self) {
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                self.live_snapshot[row][col] = self.buffer.chars[row][col].read();
+            }
+        }
+    }
+
+    // Row `distance` rows back from the bottom of the live screen (0 = the
+    // bottom row), sourced from the pre-scroll snapshot for the most recent
+    // `BUFFER_HEIGHT` rows and from the scrollback ring beyond that.
+    fn row_at_distance(&self, distance: usize) -> [Char; BUFFER_WIDTH] {
+        if distance < BUFFER_HEIGHT {
+            self.live_snapshot[BUFFER_HEIGHT - 1 - distance]
+        } else {
+            self.scrollback.row_from_newest(distance - BUFFER_HEIGHT)
+        }
+    }
+
+    fn repaint_viewport(&mut self) {
+        for screen_row in 0..BUFFER_HEIGHT {
+            let distance = self.view_offset + (BUFFER_HEIGHT - 1 - screen_row);
+            let row = self.row_at_distance(distance);
+            for col in 0..BUFFER_WIDTH {
+                self.buffer.chars[screen_row][col].write(row[col]);
+            }
+        }
+    }
+
     pub fn clear_screen(&mut self, color: Color) {
         let blank = Char {
             ascii_character: b' ',
@@ -111,7 +437,7 @@ impl Writer {
         };
         for row in 0..BUFFER_HEIGHT {
             for col in 0..BUFFER_WIDTH {
-                self.buffer.chars[row][col] = blank;
+                self.buffer.chars[row][col].write(blank);
             }
         }
     }
@@ -122,7 +448,7 @@ impl Writer {
             color_code: self.color_code,
         };
         for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col] = blank;
+            self.buffer.chars[row][col].write(blank);
         }
     }
     pub fn write_string(&mut self, s: &str) {
@@ -135,6 +461,7 @@ impl Writer {
         self.color_code = ColorCode::new(foreground, background);
     }
     pub fn backspace(&mut self) {
+        self.snap_to_bottom();
         if self.column_position == 0 {
             return;
         }
@@ -153,6 +480,62 @@ impl Writer {
         }
         self.move_cursor(self.column_position + 1)
     }
+    fn column_position(&self) -> usize {
+        self.column_position
+    }
+    // Reads the bottom row back out of the VGA buffer, trimming trailing
+    // spaces, so a line editor can reconstruct what's currently on screen
+    // without keeping its own shadow copy.
+    fn read_row(&self) -> ([u8; ACTUAL_BUFFER_WIDTH], usize) {
+        let mut bytes = [b' '; ACTUAL_BUFFER_WIDTH];
+        let mut len = 0;
+        for col in 0..ACTUAL_BUFFER_WIDTH {
+            let byte = self.buffer.chars[BUFFER_HEIGHT - 1][col]
+                .read()
+                .ascii_character;
+            bytes[col] = byte;
+            if byte != b' ' {
+                len = col + 1;
+            }
+        }
+        (bytes, len)
+    }
+    // Shifts every cell from the cursor to the end of the line one column
+    // to the right (dropping whatever falls off the end), then writes
+    // `byte` at the cursor and advances it - mid-line insertion instead of
+    // only ever appending.
+    fn insert_at_cursor(&mut self, byte: u8) {
+        self.snap_to_bottom();
+        if self.column_position >= ACTUAL_BUFFER_WIDTH {
+            return;
+        }
+        let row = self.row_position;
+        let mut col = ACTUAL_BUFFER_WIDTH - 1;
+        while col > self.column_position {
+            let moved = self.buffer.chars[row][col - 1].read();
+            self.buffer.chars[row][col].write(moved);
+            col -= 1;
+        }
+        self.set_char(byte);
+        self.move_cursor(self.column_position + 1);
+    }
+    // Shifts every cell right of the cursor one column to the left,
+    // blanking the vacated tail cell - mid-line deletion instead of only
+    // ever backspacing the last character.
+    fn delete_at_cursor(&mut self) {
+        self.snap_to_bottom();
+        let row = self.row_position;
+        let mut col = self.column_position;
+        while col + 1 < ACTUAL_BUFFER_WIDTH {
+            let moved = self.buffer.chars[row][col + 1].read();
+            self.buffer.chars[row][col].write(moved);
+            col += 1;
+        }
+        self.buffer.chars[row][col].write(Char {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        });
+    }
 }
 
 impl fmt::Write for Writer {
@@ -165,11 +548,46 @@ impl fmt::Write for Writer {
 lazy_static! {
     pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
         column_position: 0,
+        row_position: BUFFER_HEIGHT - 1,
         color_code: ColorCode::new(Color::Yellow, Color::Black),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        ansi_state: AnsiState::Ground,
+        csi_params: [0; MAX_CSI_PARAMS],
+        csi_param_count: 0,
+        csi_raw: [0; MAX_CSI_RAW],
+        csi_raw_len: 0,
+        scrollback: unsafe { &mut *core::ptr::addr_of_mut!(SCROLLBACK_STORAGE) },
+        live_snapshot: unsafe { &mut *core::ptr::addr_of_mut!(LIVE_SNAPSHOT_STORAGE) },
+        view_offset: 0,
     });
 }
 
+// Maps an SGR base color index (0-7, the 30-37/40-47 order: black, red,
+// green, yellow, blue, magenta, cyan, white) onto this crate's `Color`
+// enum, whose names and ordering don't match ANSI's.
+const SGR_TO_COLOR: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LighGrey,
+];
+
+// Maps the 16 SGR color indices (0-7 as above, 8-15 their bright/90-97
+// variants) onto a raw `Color` discriminant. The bright variant of a color
+// sits exactly 8 discriminants above its base in the `Color` enum.
+fn sgr_color_index(index: u16) -> u8 {
+    let base = SGR_TO_COLOR[(index % 8) as usize] as u8;
+    if index >= 8 {
+        base + 8
+    } else {
+        base
+    }
+}
+
 #[doc(hidden)]
 pub fn print(args: fmt::Arguments) {
     interrupts::without_interrupts(|| {
@@ -204,3 +622,47 @@ pub fn cursor_front() {
         WRITER.lock().cursor_front();
     });
 }
+
+pub fn enable_cursor() {
+    interrupts::without_interrupts(|| {
+        WRITER.lock().enable_cursor();
+    });
+}
+
+pub fn disable_cursor() {
+    interrupts::without_interrupts(|| {
+        WRITER.lock().disable_cursor();
+    });
+}
+
+pub(crate) fn column_position() -> usize {
+    interrupts::without_interrupts(|| WRITER.lock().column_position())
+}
+
+pub(crate) fn read_row() -> ([u8; ACTUAL_BUFFER_WIDTH], usize) {
+    interrupts::without_interrupts(|| WRITER.lock().read_row())
+}
+
+pub(crate) fn insert_at_cursor(byte: u8) {
+    interrupts::without_interrupts(|| {
+        WRITER.lock().insert_at_cursor(byte);
+    });
+}
+
+pub(crate) fn delete_at_cursor() {
+    interrupts::without_interrupts(|| {
+        WRITER.lock().delete_at_cursor();
+    });
+}
+
+pub fn scroll_up(lines: usize) {
+    interrupts::without_interrupts(|| {
+        WRITER.lock().scroll_up(lines);
+    });
+}
+
+pub fn scroll_down(lines: usize) {
+    interrupts::without_interrupts(|| {
+        WRITER.lock().scroll_down(lines);
+    });
+}
@@ -5,13 +5,13 @@ extern crate alloc;
 /// This function is called on panic.
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
+    log!("{}\n", info);
     hlt_loop();
 }
 
 use bootloader::{entry_point, BootInfo};
 use popcorn::{
-    hlt_loop, init,
+    hlt_loop, init, log,
     low_level::vga_buffer::{clear_screen, Color, MessageToVga},
     print_with_colors, println,
 };